@@ -0,0 +1,129 @@
+// Memory-mapped audio processing unit, modeled on the NES APU's pulse and
+// noise channels. Registers live in 0xFFE0-0xFFEF; `VM::write_memory` routes
+// writes in that range here instead of storing them as plain bytes.
+
+pub const APU_REGISTERS_START: usize = 0xFFE0;
+pub const APU_REGISTERS_END: usize = 0xFFEF;
+
+pub const SQ1_PERIOD_LO: usize = 0xFFE0;
+pub const SQ1_PERIOD_HI: usize = 0xFFE1;
+pub const SQ1_DUTY_VOLUME: usize = 0xFFE2;
+
+pub const SQ2_PERIOD_LO: usize = 0xFFE4;
+pub const SQ2_PERIOD_HI: usize = 0xFFE5;
+pub const SQ2_DUTY_VOLUME: usize = 0xFFE6;
+
+pub const NOISE_VOLUME: usize = 0xFFE8;
+
+const CPU_CLOCK_HZ: f32 = 1_789_773.0; // NES-derived reference clock for period_to_freq
+
+// Classic 12.5/25/50/75% pulse duty cycles, 8 steps per period.
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [0, 0, 0, 0, 0, 0, 1, 1], // 25%
+    [0, 0, 0, 0, 1, 1, 1, 1], // 50%
+    [1, 1, 1, 1, 1, 1, 0, 0], // 75%
+];
+
+fn period_to_freq(period: u16) -> f32 {
+    CPU_CLOCK_HZ / (16.0 * (period as f32 + 1.0))
+}
+
+#[derive(Default)]
+struct SquareChannel {
+    period: u16, // 11-bit
+    duty: u8,    // 4-bit selector into DUTY_TABLE
+    volume: u8,  // 4-bit
+    phase: f32,
+}
+
+impl SquareChannel {
+    fn sample(&mut self, sample_rate: f32) -> i16 {
+        let freq = period_to_freq(self.period & 0x7ff);
+        self.phase += freq / sample_rate;
+        self.phase -= self.phase.floor();
+
+        let octant = (self.phase * 8.0) as usize % 8;
+        let duty = DUTY_TABLE[(self.duty & 0x3) as usize];
+        if duty[octant] == 1 {
+            self.volume as i16
+        } else {
+            0
+        }
+    }
+}
+
+struct NoiseChannel {
+    lfsr: u16,
+    volume: u8, // 4-bit
+}
+
+impl Default for NoiseChannel {
+    fn default() -> Self {
+        NoiseChannel {
+            lfsr: 1, // must never be seeded with 0 or the feedback loop stalls
+            volume: 0,
+        }
+    }
+}
+
+impl NoiseChannel {
+    fn sample(&mut self) -> i16 {
+        let bit0 = self.lfsr & 1;
+        let bit1 = (self.lfsr >> 1) & 1;
+        let feedback = bit0 ^ bit1;
+        self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+
+        ((self.lfsr & 1) * self.volume as u16) as i16
+    }
+}
+
+pub struct Apu {
+    square1: SquareChannel,
+    square2: SquareChannel,
+    noise: NoiseChannel,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            square1: SquareChannel::default(),
+            square2: SquareChannel::default(),
+            noise: NoiseChannel::default(),
+        }
+    }
+
+    pub fn write_register(&mut self, addr: usize, value: u8) {
+        match addr {
+            SQ1_PERIOD_LO => self.square1.period = (self.square1.period & 0x700) | value as u16,
+            SQ1_PERIOD_HI => {
+                self.square1.period = (self.square1.period & 0x0ff) | ((value as u16 & 0x7) << 8)
+            }
+            SQ1_DUTY_VOLUME => {
+                self.square1.duty = (value >> 4) & 0xf;
+                self.square1.volume = value & 0xf;
+            }
+            SQ2_PERIOD_LO => self.square2.period = (self.square2.period & 0x700) | value as u16,
+            SQ2_PERIOD_HI => {
+                self.square2.period = (self.square2.period & 0x0ff) | ((value as u16 & 0x7) << 8)
+            }
+            SQ2_DUTY_VOLUME => {
+                self.square2.duty = (value >> 4) & 0xf;
+                self.square2.volume = value & 0xf;
+            }
+            NOISE_VOLUME => self.noise.volume = value & 0xf,
+            _ => {}
+        }
+    }
+
+    /// Renders and mixes one output sample at `sample_rate` Hz, ready to be
+    /// pushed straight into an SDL2 `AudioQueue<i16>`.
+    pub fn next_sample(&mut self, sample_rate: f32) -> i16 {
+        let sq1 = self.square1.sample(sample_rate);
+        let sq2 = self.square2.sample(sample_rate);
+        let noise = self.noise.sample();
+
+        let mixed = (sq1 + sq2 + noise) as i32 * 1024;
+        mixed.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+    }
+}
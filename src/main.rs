@@ -1,16 +1,33 @@
+mod apu;
 mod renderer;
 mod utils;
 mod vm;
 
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use std::time::{Duration, Instant};
 use utils::keyboard::keycode_to_vm_input;
 
+const AUDIO_SAMPLE_RATE: i32 = 44_100;
+
+// Target clock rate the scheduler paces execution to: one full frame is
+// `CYCLES_PER_LINE * LINES_PER_FRAME` cycles, at 60 frames per second.
+const TARGET_CLOCK_HZ: f64 = (vm::CYCLES_PER_LINE * vm::LINES_PER_FRAME * 60) as f64;
+
 fn main() -> Result<(), String> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
+    let audio_subsystem = sdl_context.audio()?;
+
+    let audio_spec = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE),
+        channels: Some(1),
+        samples: None,
+    };
+    let audio_queue: AudioQueue<i16> = audio_subsystem.open_queue(None, &audio_spec)?;
+    audio_queue.resume();
 
     let window = video_subsystem
         .window("Fantasy Console Emulator", 640, 480)
@@ -23,6 +40,7 @@ fn main() -> Result<(), String> {
 
     let mut vm = vm::VM::new();
     vm.load_bios("roms/bios.bin");
+    vm.load_tile_rom("roms/tiles.bin");
     let renderer = renderer::Renderer::new();
 
     let sample_program = vec![
@@ -46,7 +64,6 @@ fn main() -> Result<(), String> {
     vm.load_program(sample_program);
 
     let mut last_update = Instant::now();
-    let mut frame_counter = 0;
 
     'running: loop {
         for event in event_pump.poll_iter() {
@@ -72,30 +89,45 @@ fn main() -> Result<(), String> {
 
         vm.update_timer(delta.as_millis() as u64);
 
-        for _ in 0..1000 {
-            if !vm.halted {
-                vm.run_cycle();
-            }
-        }
-
-        // Simulate a VBlank interrupt every 60th of a second
-        frame_counter += 1;
-        if frame_counter % 60 == 0 {
-            vm.vblank_interrupt();
-        }
+        // Run exactly as many cycles as elapsed wall-clock time earns at the
+        // target clock rate; VBlank fires on its own once the VM's scanline
+        // counter crosses into the vblank region, so no modulo counting here.
+        // Any overshoot from a prior call is carried internally as debt and
+        // paid down out of this frame's budget, so it's never just dropped.
+        let budget = (TARGET_CLOCK_HZ * delta.as_secs_f64()) as u64;
+        vm.run_for_cycles(budget);
 
         // Only render if the screen is dirty
         if vm.screen_dirty {
             canvas.set_draw_color(Color::RGB(0, 0, 0));
             canvas.clear();
-            renderer.render(
-                &mut canvas,
-                &vm.memory[vm::VRAM_START..vm::VRAM_START + vm::VRAM_SIZE],
-            )?;
+            let mode = vm.display_mode();
+            let vram = if mode == vm::DISPLAY_MODE_BITMAP {
+                &vm.memory[vm::BITMAP_VRAM_START..vm::BITMAP_VRAM_START + vm::BITMAP_VRAM_SIZE]
+            } else {
+                &vm.memory[vm::VRAM_START..vm::VRAM_START + vm::VRAM_SIZE]
+            };
+            let tile_rom = &vm.memory[vm::TILE_ROM_START..vm::TILE_ROM_START + vm::TILE_ROM_SIZE];
+            let oam = &vm.memory[vm::OAM_START..vm::OAM_START + vm::OAM_SIZE];
+            renderer.render(&mut canvas, mode, vram, tile_rom, oam)?;
             canvas.present();
             vm.screen_dirty = false;
         }
 
+        // Keep the audio queue topped up with roughly one frame's worth of
+        // samples so playback stays in lockstep with the emulated machine.
+        // `AudioQueue::size()` reports queued bytes, not samples, so the
+        // sample-count low-water mark needs converting to bytes first.
+        let low_water_samples = (AUDIO_SAMPLE_RATE as u32 / 60) * 2;
+        let low_water_bytes = low_water_samples * std::mem::size_of::<i16>() as u32;
+        if audio_queue.size() < low_water_bytes {
+            let samples_per_frame = AUDIO_SAMPLE_RATE as usize / 60;
+            let samples: Vec<i16> = (0..samples_per_frame)
+                .map(|_| vm.next_audio_sample(AUDIO_SAMPLE_RATE as f32))
+                .collect();
+            audio_queue.queue_audio(&samples)?;
+        }
+
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
     }
 
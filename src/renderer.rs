@@ -1,8 +1,22 @@
+use crate::vm::{
+    BITMAP_HEIGHT, BITMAP_WIDTH, DISPLAY_MODE_BITMAP, OAM_ENTRY_SIZE, SPRITE_ATTR_ENABLED,
+    SPRITE_ATTR_FLIP_H, SPRITE_ATTR_FLIP_V, SPRITE_ATTR_PALETTE_MASK, TILE_HEIGHT, TILE_WIDTH,
+};
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
+const CANVAS_WIDTH: u32 = 640;
+const CANVAS_HEIGHT: u32 = 480;
+
+const CELL_WIDTH: i32 = 20;
+const CELL_HEIGHT: i32 = 24;
+
+// Matches bitmap mode's framebuffer-to-canvas scale, so an 8x8 sprite reads
+// as a real on-screen object instead of an 8x8 speck.
+const SPRITE_SCALE: i32 = 4;
+
 pub struct Renderer {}
 
 impl Renderer {
@@ -10,23 +24,181 @@ impl Renderer {
         Renderer {}
     }
 
-    pub fn render(&self, canvas: &mut Canvas<Window>, vram: &[u8]) -> Result<(), String> {
+    /// Draws the background layer (text or bitmap, depending on `mode`),
+    /// then blits the enabled sprites in `oam` from `tile_rom` on top.
+    pub fn render(
+        &self,
+        canvas: &mut Canvas<Window>,
+        mode: u8,
+        vram: &[u8],
+        tile_rom: &[u8],
+        oam: &[u8],
+    ) -> Result<(), String> {
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
 
+        if mode == DISPLAY_MODE_BITMAP {
+            self.render_bitmap(canvas, vram)?;
+        } else {
+            self.render_text(canvas, vram, tile_rom)?;
+        }
+
+        self.render_sprites(canvas, tile_rom, oam)?;
+
+        Ok(())
+    }
+
+    fn render_text(
+        &self,
+        canvas: &mut Canvas<Window>,
+        vram: &[u8],
+        tile_rom: &[u8],
+    ) -> Result<(), String> {
+        let pixel_w = CELL_WIDTH / TILE_WIDTH as i32;
+        let pixel_h = CELL_HEIGHT / TILE_HEIGHT as i32;
+
         for (i, &char_code) in vram.iter().enumerate() {
-            let x = (i % 40) as i32 * 20;
-            let y = (i / 40) as i32 * 24;
+            let x = (i % 40) as i32 * CELL_WIDTH;
+            let y = (i / 40) as i32 * CELL_HEIGHT;
 
             canvas.set_draw_color(Color::RGB(255, 255, 255));
-            canvas.fill_rect(Rect::new(x, y, 20, 24))?;
+            canvas.fill_rect(Rect::new(x, y, CELL_WIDTH as u32, CELL_HEIGHT as u32))?;
 
-            if char_code != 0 {
-                canvas.set_draw_color(Color::RGB(0, 0, 0));
-                canvas.fill_rect(Rect::new(x + 2, y + 2, 16, 20))?;
+            if char_code == 0 {
+                continue;
             }
+
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            blit_tile(canvas, tile_rom, char_code as usize, x, y, pixel_w, pixel_h, false, false)?;
         }
 
         Ok(())
     }
+
+    fn render_bitmap(&self, canvas: &mut Canvas<Window>, vram: &[u8]) -> Result<(), String> {
+        let scale_x = CANVAS_WIDTH / BITMAP_WIDTH as u32;
+        let scale_y = CANVAS_HEIGHT / BITMAP_HEIGHT as u32;
+
+        for (i, &pixel) in vram.iter().enumerate() {
+            let x = (i % BITMAP_WIDTH) as i32 * scale_x as i32;
+            let y = (i / BITMAP_WIDTH) as i32 * scale_y as i32;
+
+            canvas.set_draw_color(palette_color(pixel));
+            canvas.fill_rect(Rect::new(x, y, scale_x, scale_y))?;
+        }
+
+        Ok(())
+    }
+
+    fn render_sprites(
+        &self,
+        canvas: &mut Canvas<Window>,
+        tile_rom: &[u8],
+        oam: &[u8],
+    ) -> Result<(), String> {
+        for entry in oam.chunks_exact(OAM_ENTRY_SIZE) {
+            let attr = entry[3];
+            if attr & SPRITE_ATTR_ENABLED == 0 {
+                continue;
+            }
+
+            let tile_index = entry[0] as usize;
+            // OAM x/y are native-framebuffer coordinates, same space as the
+            // background layer, so they need the same scale-up to land in
+            // the right place on the canvas.
+            let x = entry[1] as i32 * SPRITE_SCALE;
+            let y = entry[2] as i32 * SPRITE_SCALE;
+            let flip_h = attr & SPRITE_ATTR_FLIP_H != 0;
+            let flip_v = attr & SPRITE_ATTR_FLIP_V != 0;
+            let palette = attr & SPRITE_ATTR_PALETTE_MASK;
+
+            canvas.set_draw_color(sprite_palette_color(palette));
+            blit_tile(
+                canvas,
+                tile_rom,
+                tile_index,
+                x,
+                y,
+                SPRITE_SCALE,
+                SPRITE_SCALE,
+                flip_h,
+                flip_v,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Blits one tile from `tile_rom` at `(x, y)`, each tile pixel drawn as a
+/// `pixel_w`x`pixel_h` rect. Color-0 (unset) bits are left transparent.
+fn blit_tile(
+    canvas: &mut Canvas<Window>,
+    tile_rom: &[u8],
+    tile_index: usize,
+    x: i32,
+    y: i32,
+    pixel_w: i32,
+    pixel_h: i32,
+    flip_h: bool,
+    flip_v: bool,
+) -> Result<(), String> {
+    let glyph = &tile_rom[tile_index * TILE_HEIGHT..tile_index * TILE_HEIGHT + TILE_HEIGHT];
+
+    for row in 0..TILE_HEIGHT {
+        let src_row = if flip_v { TILE_HEIGHT - 1 - row } else { row };
+        let byte = glyph[src_row];
+
+        for col in 0..TILE_WIDTH {
+            let src_col = if flip_h { TILE_WIDTH - 1 - col } else { col };
+            if (byte >> (7 - src_col)) & 1 == 0 {
+                continue;
+            }
+
+            canvas.fill_rect(Rect::new(
+                x + col as i32 * pixel_w,
+                y + row as i32 * pixel_h,
+                pixel_w as u32,
+                pixel_h as u32,
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands a packed RRRGGGBB byte into an RGB888 color.
+fn palette_color(byte: u8) -> Color {
+    let r = (byte >> 5) & 0x7;
+    let g = (byte >> 2) & 0x7;
+    let b = byte & 0x3;
+
+    Color::RGB(r * 36, g * 36, b * 85)
+}
+
+/// The 4-bit sprite attribute palette: a dedicated 16-entry table rather
+/// than squeezing into the 8-bit bitmap-mode decoder, where a 4-bit value
+/// never sets red and only reaches the lower half of green.
+const SPRITE_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (255, 255, 255),
+    (255, 0, 0),
+    (0, 255, 0),
+    (0, 0, 255),
+    (255, 255, 0),
+    (0, 255, 255),
+    (255, 0, 255),
+    (192, 192, 192),
+    (255, 128, 0),
+    (128, 0, 255),
+    (0, 128, 128),
+    (128, 128, 0),
+    (0, 128, 0),
+    (128, 0, 0),
+    (0, 0, 128),
+];
+
+fn sprite_palette_color(index: u8) -> Color {
+    let (r, g, b) = SPRITE_PALETTE[(index & 0x0f) as usize];
+    Color::RGB(r, g, b)
 }
@@ -1,12 +1,56 @@
+use crate::apu::{Apu, APU_REGISTERS_END, APU_REGISTERS_START};
 use rand::Rng;
 
 pub const MEMORY_SIZE: usize = 65536; // 64KB of memory
-pub const VRAM_START: usize = 0xF000; // Start of video memory
+pub const VRAM_START: usize = 0xF000; // Start of text-mode video memory
 pub const VRAM_SIZE: usize = 1000; // 40x25 text mode display
 pub const INPUT_REGISTER: usize = 0xFFF0; // Memory-mapped input register
 pub const OUTPUT_REGISTER: usize = 0xFFF1; // Memory-mapped output register
 pub const RANDOM_REGISTER: usize = 0xFFF2; // Memory-mapped random number generator
 pub const TIMER_REGISTER: usize = 0xFFF3; // Memory-mapped timer register
+pub const DISPLAY_MODE_REGISTER: usize = 0xFFF6; // Selects the active video mode
+pub const IE_REGISTER: usize = 0xFFF7; // Interrupt-enable mask
+pub const IF_REGISTER: usize = 0xFFF8; // Interrupt-flag (pending) mask
+
+pub const DISPLAY_MODE_TEXT: u8 = 0;
+pub const DISPLAY_MODE_BITMAP: u8 = 1;
+
+// Interrupt sources, as bits in IE_REGISTER / IF_REGISTER.
+pub const INT_VBLANK: u8 = 0x1;
+pub const INT_TIMER: u8 = 0x2;
+pub const INT_INPUT: u8 = 0x4;
+
+// Vector table: each entry is a little-endian 16-bit handler address.
+pub const VECTOR_VBLANK: usize = 0xFF00;
+pub const VECTOR_TIMER: usize = 0xFF02;
+pub const VECTOR_INPUT: usize = 0xFF04;
+
+// Bitmap mode framebuffer: one byte per pixel, indexing a 256-entry RRRGGGBB
+// palette. Sits well clear of both the BIOS and the text-mode VRAM.
+pub const BITMAP_VRAM_START: usize = 0xA000;
+pub const BITMAP_WIDTH: usize = 160;
+pub const BITMAP_HEIGHT: usize = 120;
+pub const BITMAP_VRAM_SIZE: usize = BITMAP_WIDTH * BITMAP_HEIGHT;
+
+// 1-bpp tile ROM shared by the text layer's glyphs and sprite shapes: one
+// byte per row, `TILE_HEIGHT` rows per tile, most-significant bit leftmost.
+pub const TILE_ROM_START: usize = 0x9000;
+pub const TILE_WIDTH: usize = 8;
+pub const TILE_HEIGHT: usize = 8;
+pub const TILE_ROM_GLYPH_COUNT: usize = 256;
+pub const TILE_ROM_SIZE: usize = TILE_ROM_GLYPH_COUNT * TILE_HEIGHT;
+
+// Object attribute table: up to OAM_SPRITE_COUNT movable sprites, each a
+// 4-byte entry of (tile index, x, y, attribute).
+pub const OAM_START: usize = 0xF400;
+pub const OAM_SPRITE_COUNT: usize = 64;
+pub const OAM_ENTRY_SIZE: usize = 4;
+pub const OAM_SIZE: usize = OAM_SPRITE_COUNT * OAM_ENTRY_SIZE;
+
+pub const SPRITE_ATTR_ENABLED: u8 = 0x80;
+pub const SPRITE_ATTR_FLIP_H: u8 = 0x40;
+pub const SPRITE_ATTR_FLIP_V: u8 = 0x20;
+pub const SPRITE_ATTR_PALETTE_MASK: u8 = 0x0f;
 
 // In vm.rs
 pub const INPUT_UP: u8 = 10;
@@ -19,7 +63,7 @@ pub const INPUT_A: u8 = 70;
 pub const INPUT_B: u8 = 80;
 pub const INPUT_NONE: u8 = 0;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Instruction {
     Nop,
     Push(i32),
@@ -52,18 +96,201 @@ pub enum Instruction {
     Sleep(u64),
     ClearScreen,
     Halt,
+    Reti,
+}
+
+/// Cycle cost of executing `instruction`, used by `VM::run_for_cycles` to
+/// budget execution against wall-clock time instead of a fixed loop count.
+fn cycle_cost(instruction: &Instruction) -> u64 {
+    match instruction {
+        Instruction::Nop => 1,
+        Instruction::Push(_) | Instruction::Pop | Instruction::Dup | Instruction::Swap => 2,
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Mod
+        | Instruction::And
+        | Instruction::Or
+        | Instruction::Xor
+        | Instruction::Not
+        | Instruction::Eq
+        | Instruction::Ne
+        | Instruction::Lt
+        | Instruction::Gt
+        | Instruction::Lte
+        | Instruction::Gte => 2,
+        Instruction::Jump(_) | Instruction::JumpIf(_) => 3,
+        Instruction::Call(_) | Instruction::Ret | Instruction::Reti => 4,
+        Instruction::Load(_) | Instruction::Store(_) | Instruction::LoadImmediate(_, _) => 3,
+        Instruction::RandomNum(_, _) => 2,
+        Instruction::Sleep(_) => 1,
+        Instruction::ClearScreen => 4,
+        Instruction::Halt => 1,
+    }
+}
+
+/// Cycles spent servicing an interrupt: pushing the return address and
+/// jumping to the handler, mirroring the cost of `Call`.
+const INTERRUPT_SERVICE_CYCLES: u64 = 4;
+
+pub const CYCLES_PER_LINE: u64 = 114;
+pub const LINES_PER_FRAME: u64 = 262;
+pub const VBLANK_START_LINE: u64 = 240;
+
+// Where `load_bios` and `load_program` write executable bytes; `pc` starts
+// here on reset.
+pub const PROGRAM_START: usize = 0x0000;
+
+// Byte encoding: the first byte of every instruction is one of these
+// opcodes. Operands follow as little-endian integers - a 16-bit address for
+// anything that names a memory location, a 4-byte i32 for immediates, an
+// 8-byte u64 for `Sleep`'s millisecond count.
+const OP_NOP: u8 = 0x00;
+const OP_PUSH: u8 = 0x01;
+const OP_POP: u8 = 0x02;
+const OP_DUP: u8 = 0x03;
+const OP_SWAP: u8 = 0x04;
+const OP_ADD: u8 = 0x05;
+const OP_SUB: u8 = 0x06;
+const OP_MUL: u8 = 0x07;
+const OP_DIV: u8 = 0x08;
+const OP_MOD: u8 = 0x09;
+const OP_AND: u8 = 0x0a;
+const OP_OR: u8 = 0x0b;
+const OP_XOR: u8 = 0x0c;
+const OP_NOT: u8 = 0x0d;
+const OP_EQ: u8 = 0x0e;
+const OP_NE: u8 = 0x0f;
+const OP_LT: u8 = 0x10;
+const OP_GT: u8 = 0x11;
+const OP_LTE: u8 = 0x12;
+const OP_GTE: u8 = 0x13;
+const OP_JUMP: u8 = 0x14;
+const OP_JUMP_IF: u8 = 0x15;
+const OP_CALL: u8 = 0x16;
+const OP_RET: u8 = 0x17;
+const OP_LOAD: u8 = 0x18;
+const OP_STORE: u8 = 0x19;
+const OP_LOAD_IMMEDIATE: u8 = 0x1a;
+const OP_RANDOM_NUM: u8 = 0x1b;
+const OP_SLEEP: u8 = 0x1c;
+const OP_CLEAR_SCREEN: u8 = 0x1d;
+const OP_HALT: u8 = 0x1e;
+const OP_RETI: u8 = 0x1f;
+
+fn read_u16(memory: &[u8; MEMORY_SIZE], addr: usize) -> u16 {
+    memory[addr] as u16 | ((memory[addr + 1] as u16) << 8)
+}
+
+fn read_i32(memory: &[u8; MEMORY_SIZE], addr: usize) -> i32 {
+    i32::from_le_bytes(memory[addr..addr + 4].try_into().unwrap())
+}
+
+fn read_u64(memory: &[u8; MEMORY_SIZE], addr: usize) -> u64 {
+    u64::from_le_bytes(memory[addr..addr + 8].try_into().unwrap())
+}
+
+/// Total encoded length (opcode byte plus operands) for `opcode`, so
+/// `decode` can bounds-check before reading any operand bytes.
+fn encoded_len(opcode: u8) -> usize {
+    match opcode {
+        OP_PUSH => 5,
+        OP_JUMP | OP_JUMP_IF | OP_CALL | OP_LOAD | OP_STORE => 3,
+        OP_LOAD_IMMEDIATE => 7,
+        OP_RANDOM_NUM | OP_SLEEP => 9,
+        _ => 1,
+    }
+}
+
+/// Serializes a program into the byte encoding `VM::decode` reads back, for
+/// `load_program` to write into memory.
+pub fn assemble(program: &[Instruction]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+
+    for instruction in program {
+        match instruction {
+            Instruction::Nop => bytes.push(OP_NOP),
+            Instruction::Push(value) => {
+                bytes.push(OP_PUSH);
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Instruction::Pop => bytes.push(OP_POP),
+            Instruction::Dup => bytes.push(OP_DUP),
+            Instruction::Swap => bytes.push(OP_SWAP),
+            Instruction::Add => bytes.push(OP_ADD),
+            Instruction::Sub => bytes.push(OP_SUB),
+            Instruction::Mul => bytes.push(OP_MUL),
+            Instruction::Div => bytes.push(OP_DIV),
+            Instruction::Mod => bytes.push(OP_MOD),
+            Instruction::And => bytes.push(OP_AND),
+            Instruction::Or => bytes.push(OP_OR),
+            Instruction::Xor => bytes.push(OP_XOR),
+            Instruction::Not => bytes.push(OP_NOT),
+            Instruction::Eq => bytes.push(OP_EQ),
+            Instruction::Ne => bytes.push(OP_NE),
+            Instruction::Lt => bytes.push(OP_LT),
+            Instruction::Gt => bytes.push(OP_GT),
+            Instruction::Lte => bytes.push(OP_LTE),
+            Instruction::Gte => bytes.push(OP_GTE),
+            Instruction::Jump(addr) => {
+                bytes.push(OP_JUMP);
+                bytes.extend_from_slice(&(*addr as u16).to_le_bytes());
+            }
+            Instruction::JumpIf(addr) => {
+                bytes.push(OP_JUMP_IF);
+                bytes.extend_from_slice(&(*addr as u16).to_le_bytes());
+            }
+            Instruction::Call(addr) => {
+                bytes.push(OP_CALL);
+                bytes.extend_from_slice(&(*addr as u16).to_le_bytes());
+            }
+            Instruction::Ret => bytes.push(OP_RET),
+            Instruction::Load(addr) => {
+                bytes.push(OP_LOAD);
+                bytes.extend_from_slice(&(*addr as u16).to_le_bytes());
+            }
+            Instruction::Store(addr) => {
+                bytes.push(OP_STORE);
+                bytes.extend_from_slice(&(*addr as u16).to_le_bytes());
+            }
+            Instruction::LoadImmediate(addr, value) => {
+                bytes.push(OP_LOAD_IMMEDIATE);
+                bytes.extend_from_slice(&(*addr as u16).to_le_bytes());
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            Instruction::RandomNum(min, max) => {
+                bytes.push(OP_RANDOM_NUM);
+                bytes.extend_from_slice(&min.to_le_bytes());
+                bytes.extend_from_slice(&max.to_le_bytes());
+            }
+            Instruction::Sleep(ms) => {
+                bytes.push(OP_SLEEP);
+                bytes.extend_from_slice(&ms.to_le_bytes());
+            }
+            Instruction::ClearScreen => bytes.push(OP_CLEAR_SCREEN),
+            Instruction::Halt => bytes.push(OP_HALT),
+            Instruction::Reti => bytes.push(OP_RETI),
+        }
+    }
+
+    bytes
 }
 
 pub struct VM {
     pub memory: [u8; MEMORY_SIZE],
     stack: Vec<i32>,
-    pub program: Vec<Instruction>,
     pc: usize,
     call_stack: Vec<usize>,
     pub halted: bool,
     timer: u64,
     pub screen_dirty: bool,
     input_state: u8,
+    pub apu: Apu,
+    interrupts_enabled: bool,
+    line: u64,
+    line_cycles: u64,
+    cycle_debt: u64,
 }
 
 impl VM {
@@ -71,19 +298,27 @@ impl VM {
         VM {
             memory: [0; MEMORY_SIZE],
             stack: Vec::new(),
-            program: Vec::new(),
             pc: 0,
             call_stack: Vec::new(),
             halted: false,
             timer: 0,
             screen_dirty: false,
             input_state: INPUT_NONE,
+            apu: Apu::new(),
+            interrupts_enabled: true,
+            line: 0,
+            line_cycles: 0,
+            cycle_debt: 0,
         }
     }
 
+    /// Assembles `program` into its byte encoding and writes it into memory
+    /// at `PROGRAM_START`, where the fetch-decode-execute loop in
+    /// `run_cycle` picks it up.
     pub fn load_program(&mut self, program: Vec<Instruction>) {
-        self.program = program;
-        self.pc = 0;
+        let bytes = assemble(&program);
+        self.memory[PROGRAM_START..PROGRAM_START + bytes.len()].copy_from_slice(&bytes);
+        self.pc = PROGRAM_START;
         self.halted = false;
     }
 
@@ -92,15 +327,149 @@ impl VM {
         self.memory[..bios.len()].copy_from_slice(&bios);
 
         // Set the program counter to the start of the BIOS
-        self.pc = 0;
+        self.pc = PROGRAM_START;
+    }
+
+    pub fn load_tile_rom(&mut self, filename: &str) {
+        let tiles = std::fs::read(filename).expect("Failed to read tile ROM file");
+        self.memory[TILE_ROM_START..TILE_ROM_START + tiles.len()].copy_from_slice(&tiles);
+    }
+
+    /// Fetches and decodes the instruction at `pc`, returning it along with
+    /// its encoded length in bytes.
+    fn decode(&self, pc: usize) -> (Instruction, usize) {
+        let opcode = self.memory[pc];
+
+        // An instruction whose operand bytes would run past the end of
+        // memory can't be fetched safely - treat it as inert rather than
+        // slicing out of bounds.
+        if pc + encoded_len(opcode) > MEMORY_SIZE {
+            return (Instruction::Nop, 1);
+        }
+
+        match opcode {
+            OP_NOP => (Instruction::Nop, 1),
+            OP_PUSH => (Instruction::Push(read_i32(&self.memory, pc + 1)), 5),
+            OP_POP => (Instruction::Pop, 1),
+            OP_DUP => (Instruction::Dup, 1),
+            OP_SWAP => (Instruction::Swap, 1),
+            OP_ADD => (Instruction::Add, 1),
+            OP_SUB => (Instruction::Sub, 1),
+            OP_MUL => (Instruction::Mul, 1),
+            OP_DIV => (Instruction::Div, 1),
+            OP_MOD => (Instruction::Mod, 1),
+            OP_AND => (Instruction::And, 1),
+            OP_OR => (Instruction::Or, 1),
+            OP_XOR => (Instruction::Xor, 1),
+            OP_NOT => (Instruction::Not, 1),
+            OP_EQ => (Instruction::Eq, 1),
+            OP_NE => (Instruction::Ne, 1),
+            OP_LT => (Instruction::Lt, 1),
+            OP_GT => (Instruction::Gt, 1),
+            OP_LTE => (Instruction::Lte, 1),
+            OP_GTE => (Instruction::Gte, 1),
+            OP_JUMP => (
+                Instruction::Jump(read_u16(&self.memory, pc + 1) as usize),
+                3,
+            ),
+            OP_JUMP_IF => (
+                Instruction::JumpIf(read_u16(&self.memory, pc + 1) as usize),
+                3,
+            ),
+            OP_CALL => (
+                Instruction::Call(read_u16(&self.memory, pc + 1) as usize),
+                3,
+            ),
+            OP_RET => (Instruction::Ret, 1),
+            OP_LOAD => (
+                Instruction::Load(read_u16(&self.memory, pc + 1) as usize),
+                3,
+            ),
+            OP_STORE => (
+                Instruction::Store(read_u16(&self.memory, pc + 1) as usize),
+                3,
+            ),
+            OP_LOAD_IMMEDIATE => (
+                Instruction::LoadImmediate(
+                    read_u16(&self.memory, pc + 1) as usize,
+                    read_i32(&self.memory, pc + 3),
+                ),
+                7,
+            ),
+            OP_RANDOM_NUM => (
+                Instruction::RandomNum(
+                    read_i32(&self.memory, pc + 1),
+                    read_i32(&self.memory, pc + 5),
+                ),
+                9,
+            ),
+            OP_SLEEP => (Instruction::Sleep(read_u64(&self.memory, pc + 1)), 9),
+            OP_CLEAR_SCREEN => (Instruction::ClearScreen, 1),
+            OP_HALT => (Instruction::Halt, 1),
+            OP_RETI => (Instruction::Reti, 1),
+            // Unassigned opcodes decode as Nop, so untouched (zeroed) memory
+            // is inert rather than undefined behaviour.
+            _ => (Instruction::Nop, 1),
+        }
+    }
+
+    /// Executes instructions to work off `budget` cycles, raising VBlank
+    /// exactly when the scanline counter enters the vblank region. Any
+    /// cycles owed from a prior overshoot are paid down out of `budget`
+    /// first; if `budget` doesn't cover the whole debt, the remainder stays
+    /// on the books in `cycle_debt` rather than being forgiven. Returns the
+    /// outstanding debt after this call, which the caller can ignore - it's
+    /// tracked internally and folded into the next call automatically.
+    pub fn run_for_cycles(&mut self, budget: u64) -> u64 {
+        let payment = self.cycle_debt.min(budget);
+        self.cycle_debt -= payment;
+        let runnable = budget - payment;
+
+        let mut spent = 0u64;
+        while spent < runnable {
+            if self.halted {
+                break;
+            }
+
+            let cost = self.run_cycle();
+            if cost == 0 {
+                break;
+            }
+            spent += cost;
+            self.advance_scanline(cost);
+        }
+
+        self.cycle_debt += spent.saturating_sub(runnable);
+        self.cycle_debt
     }
 
-    pub fn run_cycle(&mut self) {
-        if self.halted || self.pc >= self.program.len() {
-            return;
+    fn advance_scanline(&mut self, cycles: u64) {
+        self.line_cycles += cycles;
+        while self.line_cycles >= CYCLES_PER_LINE {
+            self.line_cycles -= CYCLES_PER_LINE;
+            let was_vblank = self.line >= VBLANK_START_LINE;
+            self.line = (self.line + 1) % LINES_PER_FRAME;
+            if !was_vblank && self.line >= VBLANK_START_LINE {
+                self.vblank_interrupt();
+            }
         }
+    }
 
-        match &self.program[self.pc].clone() {
+    /// Executes a single instruction (or services a pending interrupt) and
+    /// returns the number of cycles it cost, or 0 if nothing ran.
+    pub fn run_cycle(&mut self) -> u64 {
+        if self.service_interrupts() {
+            return INTERRUPT_SERVICE_CYCLES;
+        }
+
+        if self.halted || self.pc >= MEMORY_SIZE {
+            return 0;
+        }
+
+        let (instruction, len) = self.decode(self.pc);
+        let cost = cycle_cost(&instruction);
+
+        match &instruction {
             Instruction::Nop => {}
             Instruction::Push(value) => self.stack.push(*value),
             Instruction::Pop => {
@@ -138,25 +507,25 @@ impl VM {
             Instruction::Gte => self.compare_op(|a, b| a >= b),
             Instruction::Jump(addr) => {
                 self.pc = *addr;
-                return;
+                return cost;
             }
             Instruction::JumpIf(addr) => {
                 if let Some(value) = self.stack.pop() {
                     if value != 0 {
                         self.pc = *addr;
-                        return;
+                        return cost;
                     }
                 }
             }
             Instruction::Call(addr) => {
-                self.call_stack.push(self.pc + 1);
+                self.call_stack.push(self.pc + len);
                 self.pc = *addr;
-                return;
+                return cost;
             }
             Instruction::Ret => {
                 if let Some(addr) = self.call_stack.pop() {
                     self.pc = addr;
-                    return;
+                    return cost;
                 }
             }
             Instruction::Load(addr) => {
@@ -185,11 +554,46 @@ impl VM {
             }
             Instruction::Halt => {
                 self.halted = true;
-                return;
+                return cost;
             }
+            Instruction::Reti => {
+                if let Some(addr) = self.call_stack.pop() {
+                    self.pc = addr;
+                    self.interrupts_enabled = true;
+                    return cost;
+                }
+            }
+        }
+
+        self.pc += len;
+        cost
+    }
+
+    /// Jumps to the highest-priority pending, enabled interrupt's handler,
+    /// mirroring `Call`. Returns `true` if an interrupt was serviced.
+    fn service_interrupts(&mut self) -> bool {
+        if !self.interrupts_enabled {
+            return false;
+        }
+
+        let pending = self.memory[IE_REGISTER] & self.memory[IF_REGISTER];
+        if pending == 0 {
+            return false;
         }
 
-        self.pc += 1;
+        let (flag, vector) = if pending & INT_VBLANK != 0 {
+            (INT_VBLANK, VECTOR_VBLANK)
+        } else if pending & INT_TIMER != 0 {
+            (INT_TIMER, VECTOR_TIMER)
+        } else {
+            (INT_INPUT, VECTOR_INPUT)
+        };
+
+        self.memory[IF_REGISTER] &= !flag;
+        self.interrupts_enabled = false;
+        self.call_stack.push(self.pc);
+        self.pc = self.memory[vector] as usize | ((self.memory[vector + 1] as usize) << 8);
+        true
     }
 
     fn binary_op<F>(&mut self, op: F)
@@ -232,58 +636,192 @@ impl VM {
                 self.memory[addr] = value;
                 self.screen_dirty = true;
             }
+            addr if (BITMAP_VRAM_START..BITMAP_VRAM_START + BITMAP_VRAM_SIZE).contains(&addr) => {
+                self.memory[addr] = value;
+                self.screen_dirty = true;
+            }
+            addr if (OAM_START..OAM_START + OAM_SIZE).contains(&addr) => {
+                self.memory[addr] = value;
+                self.screen_dirty = true;
+            }
+            addr if (APU_REGISTERS_START..=APU_REGISTERS_END).contains(&addr) => {
+                self.apu.write_register(addr, value);
+            }
             _ => self.memory[addr] = value,
         }
     }
 
+    /// Renders one mixed audio sample for the frontend to queue to its
+    /// audio device.
+    pub fn next_audio_sample(&mut self, sample_rate: f32) -> i16 {
+        self.apu.next_sample(sample_rate)
+    }
+
     pub fn update_timer(&mut self, delta_ms: u64) {
         if self.timer > 0 {
             self.timer = self.timer.saturating_sub(delta_ms);
+            if self.timer == 0 {
+                self.memory[IF_REGISTER] |= INT_TIMER;
+            }
         }
     }
 
     pub fn set_input(&mut self, input: u8) {
         self.input_state = input;
+        if input != INPUT_NONE {
+            self.memory[IF_REGISTER] |= INT_INPUT;
+        }
     }
 
     pub fn check_input(&self) -> u8 {
         self.input_state
     }
 
+    pub fn display_mode(&self) -> u8 {
+        self.memory[DISPLAY_MODE_REGISTER]
+    }
+
     pub fn vblank_interrupt(&mut self) {
         // Simulate the vertical blanking interval
 
         // 1. Update timers
-        // In many systems, timers are updated during VBlank
-        if self.timer > 0 {
-            self.timer -= 1;
-        }
+        // Timer ticking lives solely in `update_timer` now, which is the
+        // only path that sets INT_TIMER when the timer reaches zero; a
+        // second decrement here would race it without ever raising the
+        // interrupt.
 
         // 2. Handle sound
-        // If you implement sound, you might update sound registers here
+        // Sound no longer needs a VBlank hook: programs drive the Apu
+        // directly through the memory-mapped registers in 0xFFE0-0xFFEF.
 
         // 3. Update input
         // Some systems read input during VBlank
         // For now, we'll just ensure the input state is current
         self.memory[INPUT_REGISTER] = self.input_state;
 
-        // 4. Trigger any VBlank-specific interrupts
-        // In a more complex system, you might have interrupt vectors
-        // For now, we'll just set a flag that could be checked by the program
-        self.memory[0xFFF4] = 1; // Set a VBlank flag at address 0xFFF4
-
-        // 5. Signal that it's safe to update the screen
-        // This is what we're using the screen_dirty flag for
-        if self.screen_dirty {
-            // In a real system, you might set a flag that the program can check
-            // to know it's safe to update the screen
-            self.memory[0xFFF5] = 1; // Set a "safe to draw" flag at address 0xFFF5
-        }
+        // 4. Trigger the VBlank interrupt
+        // Programs register a handler in the vector table and enable
+        // INT_VBLANK in IE_REGISTER instead of polling a flag byte.
+        self.memory[IF_REGISTER] |= INT_VBLANK;
 
-        // 6. Reset the screen_dirty flag
+        // 5. Reset the screen_dirty flag
         // This is done here rather than in the rendering code because
         // in a real system, the VBlank period is when you know the screen
         // isn't being actively drawn to
         self.screen_dirty = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assemble_decode_roundtrip_covers_every_opcode() {
+        let program = vec![
+            Instruction::Nop,
+            Instruction::Push(42),
+            Instruction::Pop,
+            Instruction::Dup,
+            Instruction::Swap,
+            Instruction::Add,
+            Instruction::Sub,
+            Instruction::Mul,
+            Instruction::Div,
+            Instruction::Mod,
+            Instruction::And,
+            Instruction::Or,
+            Instruction::Xor,
+            Instruction::Not,
+            Instruction::Eq,
+            Instruction::Ne,
+            Instruction::Lt,
+            Instruction::Gt,
+            Instruction::Lte,
+            Instruction::Gte,
+            Instruction::Jump(0x1234),
+            Instruction::JumpIf(0x1235),
+            Instruction::Call(0x1236),
+            Instruction::Ret,
+            Instruction::Load(0x1237),
+            Instruction::Store(0x1238),
+            Instruction::LoadImmediate(0x1239, -7),
+            Instruction::RandomNum(1, 6),
+            Instruction::Sleep(1000),
+            Instruction::ClearScreen,
+            Instruction::Halt,
+            Instruction::Reti,
+        ];
+
+        let bytes = assemble(&program);
+        let mut vm = VM::new();
+        vm.memory[..bytes.len()].copy_from_slice(&bytes);
+
+        let mut pc = 0;
+        for instruction in &program {
+            let (decoded, len) = vm.decode(pc);
+            assert_eq!(&decoded, instruction, "mismatch decoding at pc {pc}");
+            pc += len;
+        }
+    }
+
+    #[test]
+    fn decode_bounds_checks_near_top_of_memory() {
+        let mut vm = VM::new();
+        // OP_LOAD_IMMEDIATE encodes to 7 bytes; place it 3 bytes from the
+        // end of memory so its operands would run past MEMORY_SIZE.
+        let pc = MEMORY_SIZE - 3;
+        vm.memory[pc] = OP_LOAD_IMMEDIATE;
+
+        let (instruction, len) = vm.decode(pc);
+        assert_eq!(instruction, Instruction::Nop);
+        assert_eq!(len, 1);
+    }
+
+    #[test]
+    fn service_interrupts_honours_vblank_timer_input_priority() {
+        let mut vm = VM::new();
+        vm.memory[IE_REGISTER] = INT_VBLANK | INT_TIMER | INT_INPUT;
+        vm.memory[IF_REGISTER] = INT_TIMER | INT_INPUT;
+        vm.memory[VECTOR_TIMER] = 0x34;
+        vm.memory[VECTOR_TIMER + 1] = 0x12;
+        vm.memory[VECTOR_INPUT] = 0x78;
+        vm.memory[VECTOR_INPUT + 1] = 0x56;
+
+        // TIMER outranks INPUT when VBLANK isn't pending.
+        assert!(vm.service_interrupts());
+        assert_eq!(vm.pc, 0x1234);
+        assert_eq!(vm.memory[IF_REGISTER], INT_INPUT);
+
+        // service_interrupts disables further servicing until the handler
+        // re-enables them (normally via Reti); simulate that here so the
+        // next-highest-priority bit gets its turn.
+        vm.interrupts_enabled = true;
+        assert!(vm.service_interrupts());
+        assert_eq!(vm.pc, 0x5678);
+        assert_eq!(vm.memory[IF_REGISTER], 0);
+    }
+
+    #[test]
+    fn run_for_cycles_carries_debt_instead_of_discarding_it() {
+        let mut vm = VM::new();
+        vm.load_program(vec![Instruction::Nop, Instruction::Jump(0)]);
+
+        // A budget that can only pay for the Nop overshoots once the Jump
+        // (cost 3) runs, leaving debt on the books.
+        vm.run_for_cycles(2);
+        assert!(
+            vm.cycle_debt > 0,
+            "expected an overshoot to be recorded as debt"
+        );
+        let debt_after_first_run = vm.cycle_debt;
+
+        // A second call with a budget smaller than the outstanding debt must
+        // not forgive the remainder - it should only pay down what the
+        // budget actually covers.
+        let small_budget = 1;
+        let remaining_debt = vm.run_for_cycles(small_budget);
+        assert_eq!(remaining_debt, debt_after_first_run - small_budget);
+        assert_eq!(vm.cycle_debt, debt_after_first_run - small_budget);
+    }
+}